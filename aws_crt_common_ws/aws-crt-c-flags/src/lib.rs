@@ -2,10 +2,16 @@ use cc::Build;
 use fs_extra;
 use fs_extra::dir::CopyOptions;
 use gag::Gag;
+use jobserver::Client;
+use petgraph::algo::is_cyclic_directed;
+use petgraph::graph::{DiGraph, NodeIndex};
 use serde::{Deserialize, Serialize};
 use serde_json::Result;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs;
+use std::hash::{Hash, Hasher};
 use std::path::Path;
 use std::path::PathBuf;
 
@@ -24,6 +30,29 @@ pub struct CRTModuleBuildInfo {
     include_dirs: Vec<PathBuf>,
     #[serde(skip_serializing, skip_deserializing)]
     build_toolchain: Build,
+    /// Memoized results of `check_c_source_compiles` and friends, keyed on a hash of
+    /// (compiler identity, source text, include dirs), so repeated probes are free. The module's
+    /// cflags are not part of the key.
+    #[serde(skip_serializing, skip_deserializing)]
+    probe_cache: HashMap<u64, bool>,
+    /// Source files added via `add_file_to_build`, tracked separately from `build_toolchain` since `cc::Build`
+    /// doesn't expose its own file list back to us. Used to compute the up-to-date fingerprint.
+    #[serde(skip_serializing, skip_deserializing)]
+    source_files: Vec<PathBuf>,
+    /// When set, `run_build` always recompiles, ignoring a matching fingerprint.
+    #[serde(skip_serializing, skip_deserializing)]
+    force_rebuild: bool,
+    /// Caps how many translation units `compile()` builds concurrently. `None` leaves cargo's own
+    /// jobserver allotment as the limit.
+    #[serde(skip_serializing, skip_deserializing)]
+    max_parallelism: Option<usize>,
+    /// When set, `run_build` compiles serially even if a cargo jobserver is available.
+    #[serde(skip_serializing, skip_deserializing)]
+    parallel_disabled: bool,
+    /// Cached jobserver client, constructed at most once and held for the life of this object. See
+    /// `jobserver_client()`.
+    #[serde(skip_serializing, skip_deserializing)]
+    jobserver_client: Option<Client>,
 }
 
 impl CRTModuleBuildInfo {
@@ -41,6 +70,15 @@ impl CRTModuleBuildInfo {
     /// let build_info = CRTModuleBuildInfo::new("aws_crt_common_sys");
     /// ```
     pub fn new(module_name: &str) -> CRTModuleBuildInfo {
+        // `cc` scrapes TARGET/HOST from the environment itself, but we pin them explicitly here so
+        // `build_toolchain.get_compiler()` (used by `target_is_msvc`) is guaranteed to resolve against
+        // the exact same TARGET this struct reports via `get_target`/`is_cross_compiling`, rather than
+        // relying on `cc`'s env lookup staying in sync with ours.
+        let mut build_toolchain = Build::new();
+        build_toolchain
+            .target(&env::var("TARGET").unwrap_or_default())
+            .host(&env::var("HOST").unwrap_or_default());
+
         CRTModuleBuildInfo {
             crt_module_name: module_name.parse().unwrap(),
             crt_module_deps: vec![],
@@ -53,7 +91,13 @@ impl CRTModuleBuildInfo {
             lib_name: module_name.parse().unwrap(),
             linker_path: Option::from(PathBuf::from(env::var_os("OUT_DIR").unwrap())),
             include_dirs: vec![],
-            build_toolchain: Build::new(),
+            build_toolchain,
+            probe_cache: HashMap::new(),
+            source_files: vec![],
+            force_rebuild: false,
+            max_parallelism: None,
+            parallel_disabled: false,
+            jobserver_client: None,
         }
     }
 
@@ -320,11 +364,15 @@ impl CRTModuleBuildInfo {
     /// Adds the file at path to the build tree
     pub fn add_file_to_build(&mut self, path: &Path) -> &mut CRTModuleBuildInfo {
         self.build_toolchain.file(path);
+        self.source_files.push(path.to_path_buf());
         self
     }
 
     /// Attempts to compile, `to_compile` and returns a result on whether or not it succeeded.
     /// This is useful for testing compiler capabilities before including a file or flag in your build.
+    /// The probe build sees the module's `include_dirs`, so checks against third-party headers added
+    /// via `add_third_party_include_dir` resolve correctly, but it does not see cflags, since those
+    /// aren't applied to `build_toolchain` until `load_to_build`/`run_build`.
     ///
     /// # Arguments
     ///
@@ -346,6 +394,12 @@ impl CRTModuleBuildInfo {
             env::var_os("OUT_DIR").unwrap().to_str().unwrap()
         );
         test_build.out_dir(&output_location);
+        // Probes like `check_include_exists`/`check_symbol_exists` need to see the same third-party
+        // include dirs the real build will, or a header added via `add_third_party_include_dir` would
+        // wrongly report as missing.
+        for include in &self.include_dirs {
+            test_build.include(include);
+        }
         fs::create_dir_all(&output_location).expect("creation of try compile directory failed");
         let target_location = format!(
             "{}/compiler_checks/check.c",
@@ -353,14 +407,426 @@ impl CRTModuleBuildInfo {
         );
         fs::write(Path::new(&target_location.as_str()), to_compile).expect("File write failed");
         test_build.file(&target_location);
-        let res = test_build.try_compile("test");
+        // A one-file probe compile has nothing to gain from parallelism, so pin `NUM_JOBS` to 1 for it.
+        // This only bounds `cc`'s in-process pool when no jobserver is inherited; with an inherited
+        // jobserver `cc` ignores `NUM_JOBS` (see `with_num_jobs_override`).
+        let res = CRTModuleBuildInfo::with_num_jobs_override(Some("1"), || {
+            test_build.try_compile("test")
+        });
         fs::remove_dir_all(&output_location).expect("Cleanup of try compile step failed!");
         res
     }
 
+    /// Computes a cache key for a compiler capability probe from the compiler identity, the source being
+    /// probed, and the module's `include_dirs`. Two probes only share a cached result if all three
+    /// match, so switching toolchains or include dirs invalidates prior answers. The module's cflags
+    /// are not hashed, so changing them does not invalidate a cached result.
+    fn probe_cache_key(&self, source: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.build_toolchain.get_compiler().path().hash(&mut hasher);
+        source.hash(&mut hasher);
+        // cflags aren't applied to `build_toolchain` until `load_to_build`/`run_build`, so they
+        // can't be part of the probe's cache key. `include_dirs` *are* visible to the probe (see
+        // `try_compile`), so a change to them must invalidate any cached result.
+        self.include_dirs.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Attempts to compile `code` as a standalone translation unit and caches whether it succeeded,
+    /// mirroring CMake's `check_c_source_compiles`. Repeated probes of the same source against the
+    /// same compiler and include dirs are served from `probe_cache` instead of invoking the compiler
+    /// again. Note that probes only see the module's `include_dirs`, not its cflags, since cflags
+    /// aren't applied to the toolchain until `load_to_build`/`run_build`.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - C source to attempt compilation of.
+    pub fn check_c_source_compiles(&mut self, code: &str) -> bool {
+        let key = self.probe_cache_key(code);
+        if let Some(cached) = self.probe_cache.get(&key) {
+            return *cached;
+        }
+
+        let result = self.try_compile(code).is_ok();
+        self.probe_cache.insert(key, result);
+        result
+    }
+
+    /// Checks whether `symbol` is declared by any of `headers`, mirroring CMake's `check_symbol_exists`:
+    /// it generates a translation unit that includes each header and takes the symbol's address.
+    ///
+    /// # Arguments
+    ///
+    /// * `symbol` - name of the function or variable to probe for.
+    /// * `headers` - headers to include before referencing `symbol`.
+    pub fn check_symbol_exists(&mut self, symbol: &str, headers: &[&str]) -> bool {
+        let mut code = String::new();
+        for header in headers {
+            code.push_str(&format!("#include <{}>\n", header));
+        }
+        code.push_str(&format!(
+            "int main(void) {{\n    void *symbol_ptr = (void *)&{};\n    return symbol_ptr != 0 ? 0 : 1;\n}}\n",
+            symbol
+        ));
+
+        self.check_c_source_compiles(&code)
+    }
+
+    /// Checks whether `header` can be included at all, mirroring CMake's `check_include_exists`.
+    ///
+    /// # Arguments
+    ///
+    /// * `header` - header name to probe, e.g. `"sys/random.h"`.
+    pub fn check_include_exists(&mut self, header: &str) -> bool {
+        let code = format!("#include <{}>\nint main(void) {{ return 0; }}\n", header);
+        self.check_c_source_compiles(&code)
+    }
+
+    /// Checks whether `flag` is accepted by the active compiler and, if so, defines `define_name` as `1`
+    /// for this module (transitively, via `add_public_define`). Mirrors a CMake
+    /// `check_c_compiler_flag` immediately followed by `add_definitions` on success.
+    ///
+    /// # Arguments
+    ///
+    /// * `flag` - compiler flag to probe, e.g. `"-mcrc32"`.
+    /// * `define_name` - define to set if `flag` is supported.
+    pub fn check_flag_and_define(&mut self, flag: &str, define_name: &str) -> bool {
+        let supported = self
+            .build_toolchain
+            .is_flag_supported(flag)
+            .unwrap_or(false);
+
+        if supported {
+            self.add_public_define(define_name, "1");
+        }
+
+        supported
+    }
+
+    /// Generates a `config.h`-style header from caller-supplied probe results and writes it via
+    /// `write_generated_file_to_output_path`, so a sys crate can reproduce aws-c-common's `config.h`
+    /// entirely from Rust. `probe_cache` only remembers booleans keyed by a source hash, with no name
+    /// attached, so it can't drive this on its own; callers pair up the names and the `bool` results of
+    /// their own `check_*` calls (see the example below) and hand them in. Each entry is emitted the
+    /// way CMake's `configure_file` resolves a `#cmakedefine NAME`: `#define NAME 1` if detected, or a
+    /// commented-out `/* #undef NAME */` if not, so the generated header reads the same as the upstream
+    /// one.
+    ///
+    /// # Arguments
+    ///
+    /// * `defines` - pairs of `(define_name, detected)`, in the order they should appear in the header.
+    /// * `path` - location, relative to `OUT_DIR`, to write the header to.
+    ///
+    /// # Examples
+    /// ```should_panic
+    /// use aws_crt_c_flags::{CRTModuleBuildInfo};
+    /// use std::path::Path;
+    /// let mut build_info = CRTModuleBuildInfo::new("aws_crt_common_sys");
+    /// let have_getrandom = build_info.check_symbol_exists("getrandom", &["sys/random.h"]);
+    /// build_info.generate_config_header(
+    ///     &[("AWS_HAVE_GETRANDOM", have_getrandom)],
+    ///     Path::new("include/aws/common/config.h"),
+    /// );
+    /// ```
+    pub fn generate_config_header(
+        &mut self,
+        defines: &[(&str, bool)],
+        path: &Path,
+    ) -> &mut CRTModuleBuildInfo {
+        let mut contents = String::from("/* Generated by aws-crt-c-flags, do not edit by hand. */\n\n");
+
+        for (name, detected) in defines {
+            if *detected {
+                contents.push_str(&format!("#define {} 1\n", name));
+            } else {
+                contents.push_str(&format!("/* #undef {} */\n", name));
+            }
+        }
+
+        self.write_generated_file_to_output_path(&contents, path);
+        self
+    }
+
+    /// Walks the full transitive closure of `crt_module_deps`, adding one graph node per distinct
+    /// `crt_module_name` and one edge per declared dependency. Nodes are deduplicated by module name,
+    /// so a diamond dependency (two modules both depending on `aws_crt_common_sys`, say) only produces
+    /// a single node for it, regardless of how many times it's reachable.
+    fn build_dependency_graph(
+        &self,
+    ) -> (
+        DiGraph<String, ()>,
+        HashMap<String, NodeIndex>,
+        HashMap<String, CRTModuleBuildInfo>,
+    ) {
+        let mut graph = DiGraph::<String, ()>::new();
+        let mut indices: HashMap<String, NodeIndex> = HashMap::new();
+        let mut modules: HashMap<String, CRTModuleBuildInfo> = HashMap::new();
+
+        fn visit(
+            module: &CRTModuleBuildInfo,
+            graph: &mut DiGraph<String, ()>,
+            indices: &mut HashMap<String, NodeIndex>,
+            modules: &mut HashMap<String, CRTModuleBuildInfo>,
+        ) -> NodeIndex {
+            if let Some(idx) = indices.get(&module.crt_module_name) {
+                return *idx;
+            }
+
+            let idx = graph.add_node(module.crt_module_name.clone());
+            indices.insert(module.crt_module_name.clone(), idx);
+            modules.insert(module.crt_module_name.clone(), module.clone());
+
+            for dep in &module.crt_module_deps {
+                let dep_idx = visit(dep, graph, indices, modules);
+                graph.add_edge(idx, dep_idx, ());
+            }
+
+            idx
+        }
+
+        let root_idx = graph.add_node(self.crt_module_name.clone());
+        indices.insert(self.crt_module_name.clone(), root_idx);
+
+        for dep in &self.crt_module_deps {
+            let dep_idx = visit(dep, &mut graph, &mut indices, &mut modules);
+            graph.add_edge(root_idx, dep_idx, ());
+        }
+
+        (graph, indices, modules)
+    }
+
+    /// Depth-first search for a cycle in `graph`, returning the offending path (module names, in the
+    /// order they were visited, with the closing edge back to the first repeated node) if one exists.
+    /// `is_cyclic_directed` tells us a cycle exists; this walks the graph again to report *which one*,
+    /// so the panic message is actually actionable instead of just "there is a cycle somewhere".
+    fn find_cycle_path(graph: &DiGraph<String, ()>) -> Option<Vec<String>> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum State {
+            Visiting,
+            Done,
+        }
+
+        let mut state: HashMap<NodeIndex, State> = HashMap::new();
+        let mut stack: Vec<NodeIndex> = vec![];
+
+        fn dfs(
+            node: NodeIndex,
+            graph: &DiGraph<String, ()>,
+            state: &mut HashMap<NodeIndex, State>,
+            stack: &mut Vec<NodeIndex>,
+        ) -> Option<Vec<String>> {
+            stack.push(node);
+            state.insert(node, State::Visiting);
+
+            for neighbor in graph.neighbors(node) {
+                match state.get(&neighbor) {
+                    Some(State::Visiting) => {
+                        let start = stack.iter().position(|n| *n == neighbor).unwrap();
+                        let mut path: Vec<String> = stack[start..]
+                            .iter()
+                            .map(|idx| graph[*idx].clone())
+                            .collect();
+                        path.push(graph[neighbor].clone());
+                        return Some(path);
+                    }
+                    Some(State::Done) => continue,
+                    None => {
+                        if let Some(path) = dfs(neighbor, graph, state, stack) {
+                            return Some(path);
+                        }
+                    }
+                }
+            }
+
+            stack.pop();
+            state.insert(node, State::Done);
+            None
+        }
+
+        for idx in graph.node_indices() {
+            if !state.contains_key(&idx) {
+                if let Some(path) = dfs(idx, graph, &mut state, &mut stack) {
+                    return Some(path);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Resolves the transitive dependency DAG rooted at this module and folds each dependency's
+    /// *public* cflags, defines, and include dirs into `build_toolchain`, deduplicating as it goes so
+    /// diamond dependencies don't emit the same `-I`/`-D` multiple times. Panics with the offending
+    /// cycle path if the declared dependencies aren't actually acyclic.
+    fn apply_resolved_dependencies(&mut self) {
+        let (graph, _indices, modules) = self.build_dependency_graph();
+
+        if is_cyclic_directed(&graph) {
+            let cycle = CRTModuleBuildInfo::find_cycle_path(&graph)
+                .unwrap_or_else(|| vec!["<unknown>".to_string()]);
+            panic!(
+                "Circular dependency detected among crt modules: {}",
+                cycle.join(" -> ")
+            );
+        }
+
+        let order = petgraph::algo::toposort(&graph, None)
+            .expect("toposort failed after cycle check reported no cycle");
+
+        let mut seen_flags: HashSet<String> = HashSet::new();
+        let mut seen_defines: HashSet<(String, String)> = HashSet::new();
+        let mut seen_includes: HashSet<PathBuf> = HashSet::new();
+
+        // toposort orders edges source-before-target, and our edges point from a module to its
+        // dependencies, so walking in reverse applies dependencies before the modules that need them.
+        for idx in order.into_iter().rev() {
+            let name = &graph[idx];
+            if *name == self.crt_module_name {
+                continue;
+            }
+
+            let module = match modules.get(name) {
+                Some(module) => module,
+                None => continue,
+            };
+
+            for flag in &module.public_cflags {
+                if seen_flags.insert(flag.clone()) {
+                    self.build_toolchain.flag_if_supported(flag.as_str());
+                }
+            }
+
+            for define in &module.public_defines {
+                if seen_defines.insert(define.clone()) {
+                    self.build_toolchain
+                        .define(define.0.as_str(), define.1.as_str());
+                }
+            }
+
+            for include in &module.include_dirs {
+                if seen_includes.insert(include.clone()) {
+                    self.build_toolchain.include(include);
+                }
+            }
+        }
+    }
+
+    /// Returns the target triple this build is compiling for, as set by cargo in `TARGET`.
+    pub fn get_target(&self) -> String {
+        env::var("TARGET").unwrap_or_default()
+    }
+
+    /// Returns the triple of the machine running the build, as set by cargo in `HOST`.
+    pub fn get_host(&self) -> String {
+        env::var("HOST").unwrap_or_default()
+    }
+
+    /// True if `TARGET` and `HOST` differ, i.e. this invocation is cross-compiling.
+    pub fn is_cross_compiling(&self) -> bool {
+        self.get_target() != self.get_host()
+    }
+
+    /// True if the compiler resolved for `TARGET` is MSVC-like (`cl.exe`/`clang-cl`), matching
+    /// `cc::Tool::is_like_msvc` for the *target* toolchain rather than any assumption about `HOST`.
+    /// `build_toolchain` has its `target`/`host` pinned explicitly in `new()` from the same `TARGET`/
+    /// `HOST` env vars `get_target`/`get_host` read, so this can't drift from what those report.
+    fn target_is_msvc(&self) -> bool {
+        self.build_toolchain.get_compiler().is_like_msvc()
+    }
+
+    /// Checks `triple_glob` against the resolved `TARGET` triple using simple glob rules: `*` matches
+    /// any run of characters, everything else must match literally. Patterns like `*-windows-*` or
+    /// `aarch64-*` are enough to cover the triple shapes rustc actually emits.
+    fn target_matches(triple_glob: &str) -> bool {
+        glob_match(triple_glob, &env::var("TARGET").unwrap_or_default())
+    }
+
+    /// Adds a private c-flag, but only if `triple_glob` matches the resolved `TARGET` triple. Lets a
+    /// single build script describe target-gated flags (e.g. SSE4.2 intrinsics on `x86_64-*`, ARM CRC
+    /// intrinsics on `aarch64-*`) without hand-rolling `cfg!(target_os)`/`cfg!(target_arch)` branches.
+    ///
+    /// # Arguments
+    ///
+    /// * `triple_glob` - glob pattern matched against `TARGET`, e.g. `"*-windows-*"` or `"aarch64-*"`.
+    /// * `c_flag` - compiler flag to apply if the target matches.
+    pub fn add_cflag_for_target(
+        &mut self,
+        triple_glob: &str,
+        c_flag: &str,
+    ) -> &mut CRTModuleBuildInfo {
+        if CRTModuleBuildInfo::target_matches(triple_glob) {
+            self.add_private_cflag(c_flag);
+        }
+        self
+    }
+
+    /// Adds a private define, but only if `triple_glob` matches the resolved `TARGET` triple.
+    ///
+    /// # Arguments
+    ///
+    /// * `triple_glob` - glob pattern matched against `TARGET`, e.g. `"*-windows-*"` or `"aarch64-*"`.
+    /// * `key` - definition name.
+    /// * `val` - definition value.
+    pub fn add_define_for_target(
+        &mut self,
+        triple_glob: &str,
+        key: &str,
+        val: &str,
+    ) -> &mut CRTModuleBuildInfo {
+        if CRTModuleBuildInfo::target_matches(triple_glob) {
+            self.add_private_define(key, val);
+        }
+        self
+    }
+
+    /// Adds a source file to the build tree, but only if `triple_glob` matches the resolved `TARGET`
+    /// triple. Useful for excluding e.g. x86-only assembly from ARM/wasm builds.
+    ///
+    /// # Arguments
+    ///
+    /// * `triple_glob` - glob pattern matched against `TARGET`, e.g. `"*-windows-*"` or `"aarch64-*"`.
+    /// * `path` - source file to add if the target matches.
+    pub fn add_file_for_target(&mut self, triple_glob: &str, path: &Path) -> &mut CRTModuleBuildInfo {
+        if CRTModuleBuildInfo::target_matches(triple_glob) {
+            self.add_file_to_build(path);
+        }
+        self
+    }
+
+    /// Adds an assembly source file, picking the right variant for the active toolchain: MSVC compiles
+    /// `.asm` via ml/ml64, while gcc/clang compile GAS-syntax `.s`/`.S`. `path` may be given with either
+    /// extension (or none); the sibling variant for whichever toolchain is active is looked up next to
+    /// it, falling back to `path` itself if no sibling variant exists on disk. Combine with
+    /// `add_file_for_target` to exclude architecture-specific assembly (e.g. x86-only CRC32C, or ARM
+    /// CRC) from builds that don't need it.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - path to the assembly source, with or without its `.asm`/`.s`/`.S` extension.
+    pub fn add_asm_file(&mut self, path: &Path) -> &mut CRTModuleBuildInfo {
+        let stem = path.with_extension("");
+
+        let candidates: Vec<PathBuf> = if self.target_is_msvc() {
+            vec![stem.with_extension("asm"), path.to_path_buf()]
+        } else {
+            vec![
+                stem.with_extension("s"),
+                stem.with_extension("S"),
+                path.to_path_buf(),
+            ]
+        };
+
+        let resolved = candidates
+            .into_iter()
+            .find(|candidate| candidate.exists())
+            .unwrap_or_else(|| path.to_path_buf());
+
+        self.add_file_to_build(&resolved)
+    }
+
     fn load_to_build(&mut self) {
         // add default warning stuff.
-        if self.build_toolchain.get_compiler().is_like_msvc() {
+        if self.target_is_msvc() {
             self.add_private_cflag("/W4")
                 .add_private_cflag("/WX")
                 .add_private_cflag("/MP");
@@ -423,31 +889,243 @@ impl CRTModuleBuildInfo {
             self.build_toolchain.include(include);
         }
 
-        for module in &self.crt_module_deps {
-            for pub_flag in &module.public_cflags {
-                self.build_toolchain.flag(pub_flag.as_str());
-            }
+        self.apply_resolved_dependencies();
+
+        if self.shared_lib {
+            self.build_toolchain.shared_flag(true);
+        }
+    }
+
+    /// If set, the next `run_build` always recompiles, ignoring a matching fingerprint. Use this to
+    /// escape a stuck up-to-date check, e.g. when debugging the fingerprint logic itself.
+    pub fn force_rebuild(&mut self) -> &mut CRTModuleBuildInfo {
+        self.force_rebuild = true;
+        self
+    }
+
+    /// Caps how many translation units are compiled concurrently. When cargo has handed this build
+    /// script an inherited jobserver (the normal case), `cc` coordinates *through that jobserver* and
+    /// ignores `NUM_JOBS` entirely, so the cap is enforced by acquiring and holding enough extra
+    /// jobserver tokens up front that at most `n` can ever be in `cc`'s hands at once. Only when no
+    /// jobserver is inherited does `cc` fall back to an in-process pool sized from `NUM_JOBS`, and only
+    /// then does this cap take effect via that variable instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `n` - maximum number of objects to compile at once.
+    pub fn set_max_parallelism(&mut self, n: usize) -> &mut CRTModuleBuildInfo {
+        self.max_parallelism = Some(n);
+        self
+    }
 
-            for pub_define in &self.public_defines {
-                self.build_toolchain
-                    .define(pub_define.0.as_str(), pub_define.1.as_str());
+    /// Forces serial compilation, overriding the jobserver-aware parallel default. Implemented the same
+    /// way as `set_max_parallelism(1)`: by holding back the jobserver tokens `cc` would otherwise use
+    /// (or, with no inherited jobserver, by scoping `NUM_JOBS=1` around the compile).
+    pub fn disable_parallel(&mut self) -> &mut CRTModuleBuildInfo {
+        self.parallel_disabled = true;
+        self
+    }
+
+    /// Checks whether cargo handed this build script an inherited jobserver, by inspecting the
+    /// `--jobserver-auth=`/`--jobserver-fds=` argument cargo stashes in `CARGO_MAKEFLAGS` (falling back
+    /// to `MAKEFLAGS`, for a build driven by `make` directly). This is a plain string check, not a
+    /// `jobserver::Client` construction: `Client::from_env` takes ownership of the inherited pipe
+    /// descriptors, and a throwaway instance constructed just to ask "is one there?" and then dropped
+    /// would risk tearing those descriptors down before `cc`'s own jobserver client gets to use them.
+    fn jobserver_is_available() -> bool {
+        let flags = env::var("CARGO_MAKEFLAGS")
+            .or_else(|_| env::var("MAKEFLAGS"))
+            .unwrap_or_default();
+        flags.contains("--jobserver-auth=") || flags.contains("--jobserver-fds=")
+    }
+
+    /// Lazily constructs the jobserver client for this process and caches it in `jobserver_client`, so
+    /// we construct it at most once and hold it for the life of this `CRTModuleBuildInfo` rather than
+    /// creating and dropping throwaway instances that could race `cc`'s own internal jobserver client
+    /// over the inherited pipe descriptors.
+    fn jobserver_client(&mut self) -> Option<&Client> {
+        if self.jobserver_client.is_none() && CRTModuleBuildInfo::jobserver_is_available() {
+            // Safety: we only construct this once per `CRTModuleBuildInfo` (cached above) and hold it
+            // for the remaining lifetime of that object, so it is never constructed and dropped
+            // repeatedly over the same inherited descriptors the way a construct-then-drop probe would.
+            self.jobserver_client = unsafe { Client::from_env() };
+        }
+        self.jobserver_client.as_ref()
+    }
+
+    /// Temporarily overrides `NUM_JOBS` for the duration of `f`, restoring whatever was there before.
+    /// This only matters when `cc` has no inherited jobserver to coordinate through, at which point it
+    /// falls back to an in-process pool sized from `NUM_JOBS`; with an inherited jobserver, `cc` ignores
+    /// `NUM_JOBS` entirely and concurrency must instead be bounded by holding jobserver tokens (see
+    /// `compile_with_parallelism_limit`).
+    fn with_num_jobs_override<T>(value: Option<&str>, f: impl FnOnce() -> T) -> T {
+        let previous = env::var("NUM_JOBS").ok();
+
+        if let Some(value) = value {
+            env::set_var("NUM_JOBS", value);
+        }
+
+        let result = f();
+
+        match previous {
+            Some(previous) => env::set_var("NUM_JOBS", previous),
+            None => env::remove_var("NUM_JOBS"),
+        }
+
+        result
+    }
+
+    /// Compiles `self.build_toolchain` with `parallel_disabled`/`max_parallelism` actually enforced:
+    ///
+    /// * With an inherited cargo jobserver, `cc` coordinates concurrency through jobserver tokens and
+    ///   ignores `NUM_JOBS`, so we acquire and hold enough extra tokens up front to starve `cc`'s pool
+    ///   down to the requested cap (or down to the one implicit token every process already holds, for
+    ///   `disable_parallel`), then release them once `compile()` returns.
+    /// * Without an inherited jobserver, `cc` falls back to an in-process pool sized from `NUM_JOBS`, so
+    ///   we scope an override of that variable for the duration of `compile()` instead, and surface a
+    ///   `cargo:warning` since the requested cap/disable is only approximated in that case.
+    fn compile_with_parallelism_limit(&mut self) {
+        let parallel_disabled = self.parallel_disabled;
+        let max_parallelism = self.max_parallelism;
+        let lib_name = self.lib_name.clone();
+        let client = self.jobserver_client().cloned();
+
+        match client {
+            Some(client) => {
+                // Tokens beyond the one implicit token every process already holds without asking the
+                // jobserver for it; holding down to this count caps how many `cc` can acquire on top.
+                let target_extra_tokens = if parallel_disabled {
+                    Some(0)
+                } else {
+                    max_parallelism.map(|n| n.saturating_sub(1))
+                };
+
+                let mut held_tokens = Vec::new();
+                if let Some(target_extra_tokens) = target_extra_tokens {
+                    let available = client.available().unwrap_or(0);
+                    for _ in 0..available.saturating_sub(target_extra_tokens) {
+                        match client.try_acquire() {
+                            Ok(Some(acquired)) => held_tokens.push(acquired),
+                            _ => break,
+                        }
+                    }
+                }
+
+                self.build_toolchain.compile(lib_name.as_str());
+                // `held_tokens` drops here, releasing whatever we held back to the jobserver.
             }
+            None => {
+                let num_jobs_override = if parallel_disabled {
+                    Some("1".to_string())
+                } else {
+                    max_parallelism.map(|n| n.to_string())
+                };
+
+                if num_jobs_override.is_some() {
+                    println!(
+                        "cargo:warning=no cargo jobserver available, approximating requested parallelism for `{}` via NUM_JOBS",
+                        lib_name
+                    );
+                }
 
-            for include in &self.include_dirs {
-                self.build_toolchain.include(include);
+                let build_toolchain = &mut self.build_toolchain;
+                CRTModuleBuildInfo::with_num_jobs_override(num_jobs_override.as_deref(), || {
+                    build_toolchain.compile(lib_name.as_str())
+                });
             }
         }
+    }
 
-        if self.shared_lib {
-            self.build_toolchain.shared_flag(true);
+    /// Path the static library `compile()` produces, so the up-to-date check can tell whether a prior
+    /// build actually left an artifact behind (and not just a stale fingerprint file).
+    fn artifact_path(&self) -> PathBuf {
+        let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+        if self.target_is_msvc() {
+            out_dir.join(format!("{}.lib", self.lib_name))
+        } else {
+            out_dir.join(format!("lib{}.a", self.lib_name))
         }
     }
 
+    /// Path the fingerprint of the last successful build is cached at.
+    fn fingerprint_path(&self) -> PathBuf {
+        PathBuf::from(env::var_os("OUT_DIR").unwrap()).join(format!("{}.fingerprint", self.lib_name))
+    }
+
+    /// Hashes the transitive dependency tree's *public* cflags, defines, and include dirs &mdash; the
+    /// exact contributions `apply_resolved_dependencies` folds into `build_toolchain` &mdash; so that a
+    /// dependency changing a public `-D`/`-I` changes the consumer's fingerprint too.
+    fn hash_module_deps(modules: &[CRTModuleBuildInfo], hasher: &mut DefaultHasher) {
+        for module in modules {
+            module.crt_module_name.hash(hasher);
+            module.public_cflags.hash(hasher);
+            module.public_defines.hash(hasher);
+            module.include_dirs.hash(hasher);
+            CRTModuleBuildInfo::hash_module_deps(&module.crt_module_deps, hasher);
+        }
+    }
+
+    /// Hashes the sorted source file list (path + mtime), the resolved compiler identity, and the
+    /// accumulated flags/defines/include state into a single fingerprint. Two builds with the same
+    /// fingerprint produced the exact same compiler invocation, so the second one can safely skip
+    /// `compile()` entirely.
+    fn compute_fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+
+        let mut files = self.source_files.clone();
+        files.sort();
+        for file in &files {
+            file.hash(&mut hasher);
+            if let Ok(metadata) = fs::metadata(file) {
+                if let Ok(modified) = metadata.modified() {
+                    modified.hash(&mut hasher);
+                }
+            }
+        }
+
+        self.build_toolchain.get_compiler().path().hash(&mut hasher);
+        self.private_cflags.hash(&mut hasher);
+        self.public_cflags.hash(&mut hasher);
+        self.private_defines.hash(&mut hasher);
+        self.public_defines.hash(&mut hasher);
+        self.include_dirs.hash(&mut hasher);
+        self.shared_lib.hash(&mut hasher);
+        CRTModuleBuildInfo::hash_module_deps(&self.crt_module_deps, &mut hasher);
+
+        hasher.finish()
+    }
+
     /// Executes the build and if successful stores this object in the environment for the next crate to use.
     pub fn run_build(&mut self) {
         self.load_to_build();
+
+        let fingerprint = self.compute_fingerprint();
+        let fingerprint_path = self.fingerprint_path();
+        let artifact_path = self.artifact_path();
+
+        let up_to_date = !self.force_rebuild
+            && artifact_path.exists()
+            && fs::read_to_string(&fingerprint_path)
+                .map(|existing| existing.trim() == fingerprint.to_string())
+                .unwrap_or(false);
+
+        if up_to_date {
+            println!(
+                "cargo:warning=`{}` is unchanged since the last build, skipping recompilation",
+                self.lib_name
+            );
+            println!("cargo:rustc-link-lib=static={}", self.lib_name);
+            println!(
+                "cargo:rustc-link-search=native={}",
+                env::var("OUT_DIR").unwrap()
+            );
+        } else {
+            self.compile_with_parallelism_limit();
+            fs::write(&fingerprint_path, fingerprint.to_string())
+                .expect("Writing build fingerprint failed!");
+        }
+
         print!("{}", serde_json::to_string(self).unwrap().as_str());
-        self.build_toolchain.compile(self.lib_name.as_str());
 
         if self.linker_path.is_some() {
             println!(
@@ -466,4 +1144,20 @@ impl CRTModuleBuildInfo {
             serde_json::to_string(self).unwrap(),
         );
     }
-}
\ No newline at end of file
+}
+
+/// Matches `text` against `pattern` using shell-glob-like rules where `*` matches any run of
+/// characters (including none) and every other character must match literally. This is enough to
+/// express the triple shapes rustc emits (`*-windows-*`, `aarch64-*`, `wasm32-*`) without pulling in a
+/// full glob or regex crate for it.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    fn helper(pattern: &[u8], text: &[u8]) -> bool {
+        match pattern.first() {
+            None => text.is_empty(),
+            Some(b'*') => helper(&pattern[1..], text) || (!text.is_empty() && helper(pattern, &text[1..])),
+            Some(&c) => !text.is_empty() && text[0] == c && helper(&pattern[1..], &text[1..]),
+        }
+    }
+
+    helper(pattern.as_bytes(), text.as_bytes())
+}